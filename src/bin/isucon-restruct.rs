@@ -1,21 +1,29 @@
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::files::SimpleFile;
+use codespan_reporting::term;
+use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
 use heck::ToSnakeCase;
 use itertools::Itertools;
 use proc_macro2::LineColumn;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 use syn::__private::{Span, ToTokens};
 use syn::spanned::Spanned;
+use syn::visit::Visit;
 use syn::{Item, ItemFn, Token, Visibility};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "kind")]
 enum Fragment {
     EntryPoint {
         content: String,
     },
     ApiResource {
         name: String,
+        method: String,
+        path: String,
         content: String,
     },
     Function {
@@ -57,6 +65,7 @@ fn byte_offset(input: &str, location: LineColumn) -> usize {
 struct ParseContext {
     input: String,
     current_offset: usize,
+    base_dir: PathBuf,
 }
 
 impl ParseContext {
@@ -70,6 +79,48 @@ impl ParseContext {
     }
 }
 
+fn route_attr(attrs: &[syn::Attribute]) -> Option<(String, String)> {
+    use syn::{Lit, Meta, NestedMeta};
+
+    for attr in attrs {
+        let name = match attr.path.segments.last() {
+            Some(seg) => seg.ident.to_string(),
+            None => continue,
+        };
+        // `actix_web::main` is only meaningful on the entry point (handled by the `sig == "main"`
+        // branch), so it is deliberately not recognized here as a route.
+        let mut method = match name.as_str() {
+            "get" | "post" | "put" | "patch" | "delete" | "head" | "options" | "trace"
+            | "connect" => name.to_uppercase(),
+            "route" => String::new(),
+            _ => continue,
+        };
+
+        let mut path = String::new();
+        if let Ok(Meta::List(list)) = attr.parse_meta() {
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Lit(Lit::Str(lit)) if path.is_empty() => {
+                        path = lit.value();
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("method") => {
+                        if let Lit::Str(lit) = nv.lit {
+                            method = lit.value().to_uppercase();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if method.is_empty() {
+            method = "ROUTE".to_string();
+        }
+        return Some((method, path));
+    }
+    None
+}
+
 fn parse_fn_code(ctx: &mut ParseContext, item: ItemFn) -> anyhow::Result<String> {
     let mut code = String::new();
 
@@ -115,11 +166,32 @@ trait Fragments {
     where
         Self: Sized;
 
+    fn parse_dir(input: &str, base_dir: &Path) -> anyhow::Result<Self>
+    where
+        Self: Sized;
+
     fn dedup_fragments(self) -> Self
     where
         Self: Sized;
 }
 
+fn load_module(base_dir: &Path, name: &str) -> anyhow::Result<Option<Vec<Fragment>>> {
+    let file = base_dir.join(format!("{}.rs", name));
+    let mod_file = base_dir.join(name).join("mod.rs");
+
+    let path = if file.exists() {
+        file
+    } else if mod_file.exists() {
+        mod_file
+    } else {
+        return Ok(None);
+    };
+
+    let content = fs::read_to_string(&path)?;
+    let fragments = Vec::<Fragment>::parse_dir(&content, &base_dir.join(name))?;
+    Ok(Some(fragments))
+}
+
 impl Fragments for Vec<Fragment> {
     fn _parse(ctx: &mut ParseContext, items: Vec<Item>) -> anyhow::Result<Self>
     where
@@ -141,19 +213,16 @@ impl Fragments for Vec<Fragment> {
                         fragments.push(Fragment::EntryPoint { content });
                     } else {
                         item.vis = Visibility::Public(Token![pub](Span::call_site()));
+                        let route = route_attr(&item.attrs);
                         let content = comment + parse_fn_code(ctx, item)?.as_str();
 
-                        let is_api_resource = content.contains("GET")
-                            || content.contains("POST")
-                            || content.contains("PUT")
-                            || content.contains("PATCH")
-                            || content.contains("DELETE")
-                            || content.contains("web ::")
-                            || content.contains("HttpResponse")
-                            || content.contains("actix_web ::");
-
-                        if is_api_resource {
-                            fragments.push(Fragment::ApiResource { name: sig, content });
+                        if let Some((method, path)) = route {
+                            fragments.push(Fragment::ApiResource {
+                                name: sig,
+                                method,
+                                path,
+                                content,
+                            });
                         } else {
                             fragments.push(Fragment::Function { name: sig, content });
                         }
@@ -197,15 +266,16 @@ impl Fragments for Vec<Fragment> {
                 }
                 Item::Mod(item) => {
                     let sig = item.ident.to_token_stream().to_string().to_snake_case();
+                    let fragments_inner = match item.content {
+                        Some((brace, items)) => {
+                            ctx.update_offset(byte_offset(&ctx.input, brace.span.open().end()));
+                            Some(Self::_parse(ctx, items)?)
+                        }
+                        None => load_module(&ctx.base_dir, &item.ident.to_string())?,
+                    };
                     fragments.push(Fragment::Mod {
                         name: sig,
-                        fragments: item
-                            .content
-                            .map(|(brace, items)| {
-                                ctx.update_offset(byte_offset(&ctx.input, brace.span.open().end()));
-                                Self::_parse(ctx, items)
-                            })
-                            .transpose()?,
+                        fragments: fragments_inner,
                     });
                 }
                 Item::Static(mut item) => {
@@ -265,10 +335,15 @@ impl Fragments for Vec<Fragment> {
     }
 
     fn parse(input: &str) -> anyhow::Result<Self> {
+        Self::parse_dir(input, Path::new("."))
+    }
+
+    fn parse_dir(input: &str, base_dir: &Path) -> anyhow::Result<Self> {
         let ast = syn::parse_file(input)?;
         let mut ctx = ParseContext {
             input: input.to_string(),
             current_offset: 0,
+            base_dir: base_dir.to_path_buf(),
         };
         Self::_parse(&mut ctx, ast.items)
     }
@@ -312,6 +387,298 @@ impl Fragments for Vec<Fragment> {
     }
 }
 
+#[derive(Default)]
+struct RefCollector {
+    refs: std::collections::HashSet<String>,
+}
+
+impl<'ast> syn::visit::Visit<'ast> for RefCollector {
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        for segment in &path.segments {
+            self.refs.insert(segment.ident.to_string());
+        }
+        syn::visit::visit_path(self, path);
+    }
+}
+
+fn collect_refs(content: &str) -> std::collections::HashSet<String> {
+    let mut collector = RefCollector::default();
+    if let Ok(file) = syn::parse_file(content) {
+        collector.visit_file(&file);
+    }
+    collector.refs
+}
+
+fn defined_idents(content: &str) -> Vec<String> {
+    let mut names = vec![];
+    if let Ok(file) = syn::parse_file(content) {
+        for item in file.items {
+            match item {
+                Item::Fn(i) => names.push(i.sig.ident.to_string()),
+                Item::Struct(i) => names.push(i.ident.to_string()),
+                Item::Enum(i) => names.push(i.ident.to_string()),
+                Item::Trait(i) => names.push(i.ident.to_string()),
+                Item::TraitAlias(i) => names.push(i.ident.to_string()),
+                Item::Type(i) => names.push(i.ident.to_string()),
+                Item::Union(i) => names.push(i.ident.to_string()),
+                Item::Const(i) => names.push(i.ident.to_string()),
+                Item::Static(i) => names.push(i.ident.to_string()),
+                _ => {}
+            }
+        }
+    }
+    names
+}
+
+fn use_leaf_idents(content: &str) -> Vec<String> {
+    fn walk(tree: &syn::UseTree, out: &mut Vec<String>) {
+        match tree {
+            syn::UseTree::Path(p) => walk(&p.tree, out),
+            syn::UseTree::Name(n) => out.push(n.ident.to_string()),
+            syn::UseTree::Rename(r) => out.push(r.rename.to_string()),
+            syn::UseTree::Group(g) => g.items.iter().for_each(|t| walk(t, out)),
+            syn::UseTree::Glob(_) => {}
+        }
+    }
+
+    let mut out = vec![];
+    if let Ok(item) = syn::parse_str::<syn::ItemUse>(content) {
+        walk(&item.tree, &mut out);
+    }
+    out
+}
+
+fn is_prelude(name: &str) -> bool {
+    matches!(
+        name,
+        "Self"
+            | "Option"
+            | "Result"
+            | "Some"
+            | "None"
+            | "Ok"
+            | "Err"
+            | "Vec"
+            | "String"
+            | "Box"
+            | "Rc"
+            | "Arc"
+            | "Cell"
+            | "RefCell"
+            | "Mutex"
+            | "RwLock"
+            | "Cow"
+            | "HashMap"
+            | "HashSet"
+            | "BTreeMap"
+            | "BTreeSet"
+            | "VecDeque"
+            | "Duration"
+            | "Instant"
+            | "PhantomData"
+            | "Ordering"
+            | "Default"
+            | "Clone"
+            | "Copy"
+            | "Debug"
+            | "Display"
+            | "PartialEq"
+            | "Eq"
+            | "Hash"
+            | "Ord"
+            | "PartialOrd"
+            | "From"
+            | "Into"
+            | "TryFrom"
+            | "TryInto"
+            | "Iterator"
+            | "IntoIterator"
+    )
+}
+
+fn module_imports(
+    content: &str,
+    defs: &HashMap<String, String>,
+    use_fragments: &[String],
+    legacy_glob: bool,
+) -> String {
+    if legacy_glob {
+        return format!("{}\nuse crate::*;\n", use_fragments.join("\n"));
+    }
+
+    let refs = collect_refs(content);
+    let own = defined_idents(content)
+        .into_iter()
+        .collect::<std::collections::HashSet<_>>();
+
+    // Keep every external `use` verbatim: trait-in-scope imports (`use sqlx::Row;` used via
+    // `row.get(..)`), `#[macro_use]` and macro imports bring names into scope that never appear
+    // textually, so filtering them by ident would drop imports the module still needs. Only the
+    // blanket `use crate::*;` is replaced with concrete per-symbol imports.
+    let mut crate_uses = defs
+        .iter()
+        .filter(|(name, _)| refs.contains(name.as_str()) && !own.contains(name.as_str()))
+        .map(|(name, module)| format!("use crate::{}::{};", module, name))
+        .collect_vec();
+    crate_uses.sort();
+    crate_uses.dedup();
+
+    use_fragments
+        .iter()
+        .cloned()
+        .chain(crate_uses)
+        .join("\n")
+        + "\n"
+}
+
+fn unresolved_symbols(
+    content: &str,
+    defs: &HashMap<String, String>,
+    use_fragments: &[String],
+) -> Vec<String> {
+    let own = defined_idents(content)
+        .into_iter()
+        .collect::<std::collections::HashSet<_>>();
+    let imported = use_fragments
+        .iter()
+        .flat_map(|content| use_leaf_idents(content))
+        .collect::<std::collections::HashSet<_>>();
+
+    collect_refs(content)
+        .into_iter()
+        .filter(|name| {
+            name.chars().next().map_or(false, char::is_uppercase)
+                && !defs.contains_key(name)
+                && !own.contains(name)
+                && !imported.contains(name)
+                && !is_prelude(name)
+        })
+        .collect()
+}
+
+#[derive(serde::Serialize)]
+struct ItemSpan {
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+    start_byte: usize,
+    end_byte: usize,
+}
+
+#[derive(serde::Serialize)]
+struct ItemManifest {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    module_path: String,
+    span: ItemSpan,
+    refs: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<ItemManifest>,
+}
+
+fn item_refs(item: &Item) -> Vec<String> {
+    let mut collector = RefCollector::default();
+    collector.visit_item(item);
+    let mut refs = collector.refs.into_iter().collect_vec();
+    refs.sort();
+    refs
+}
+
+fn item_manifest(input: &str, items: &[Item], prefix: &str) -> Vec<ItemManifest> {
+    let mut manifest = vec![];
+
+    for item in items {
+        let start = item.span().start();
+        let end = item.span().end();
+        let span = ItemSpan {
+            start_line: start.line,
+            start_column: start.column,
+            end_line: end.line,
+            end_column: end.column,
+            start_byte: byte_offset(input, start),
+            end_byte: byte_offset(input, end),
+        };
+        let refs = item_refs(item);
+
+        let mut method = None;
+        let mut path = None;
+        let mut children = vec![];
+
+        let (kind, name, module_path) = match item {
+            Item::Fn(item) => {
+                let name = item.sig.ident.to_string();
+                if name == "main" {
+                    ("EntryPoint", Some(name), "main".to_string())
+                } else if let Some((m, p)) = route_attr(&item.attrs) {
+                    method = Some(m);
+                    path = Some(p);
+                    let module_path = format!("{}resources/{}", prefix, name);
+                    ("ApiResource", Some(name), module_path)
+                } else {
+                    let module_path = format!("{}functions/{}", prefix, name);
+                    ("Function", Some(name), module_path)
+                }
+            }
+            Item::Struct(i) => model_entry(prefix, i.ident.to_string().to_snake_case()),
+            Item::Enum(i) => model_entry(prefix, i.ident.to_string().to_snake_case()),
+            Item::Trait(i) => model_entry(prefix, i.ident.to_string().to_snake_case()),
+            Item::TraitAlias(i) => model_entry(prefix, i.ident.to_string().to_snake_case()),
+            Item::Type(i) => model_entry(prefix, i.ident.to_string().to_snake_case()),
+            Item::Union(i) => model_entry(prefix, i.ident.to_string().to_snake_case()),
+            Item::Impl(i) => {
+                model_entry(prefix, i.self_ty.to_token_stream().to_string().to_snake_case())
+            }
+            Item::Const(i) => (
+                "Const",
+                Some(i.ident.to_string()),
+                format!("{}consts", prefix),
+            ),
+            Item::Static(i) => (
+                "Const",
+                Some(i.ident.to_string()),
+                format!("{}consts", prefix),
+            ),
+            Item::Mod(item) => {
+                let name = item.ident.to_string().to_snake_case();
+                if let Some((_, items)) = &item.content {
+                    children = item_manifest(input, items, &format!("{}{}/", prefix, name));
+                }
+                ("Mod", Some(name.clone()), format!("{}{}", prefix, name))
+            }
+            _ => continue,
+        };
+
+        manifest.push(ItemManifest {
+            kind,
+            name,
+            method,
+            path,
+            module_path,
+            span,
+            refs,
+            children,
+        });
+    }
+
+    manifest
+}
+
+fn model_entry(prefix: &str, name: String) -> (&'static str, Option<String>, String) {
+    let module_path = format!("{}models/{}", prefix, name);
+    ("Model", Some(name), module_path)
+}
+
+fn manifest(input: &str) -> anyhow::Result<Vec<ItemManifest>> {
+    let ast = syn::parse_file(input)?;
+    Ok(item_manifest(input, &ast.items, ""))
+}
+
 enum Module {
     EntryPoint {
         content: String,
@@ -355,7 +722,7 @@ impl Module {
 }
 
 trait Modules {
-    fn parse(fragments: Vec<Fragment>) -> anyhow::Result<Self>
+    fn parse(fragments: Vec<Fragment>, legacy_glob: bool) -> anyhow::Result<Self>
     where
         Self: Sized;
 
@@ -365,28 +732,72 @@ trait Modules {
 }
 
 impl Modules for Vec<Module> {
-    fn parse(fragments: Vec<Fragment>) -> anyhow::Result<Self>
+    fn parse(fragments: Vec<Fragment>, legacy_glob: bool) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
         let fragments = fragments.dedup_fragments();
 
-        let use_text = fragments
+        let use_fragments = fragments
             .iter()
             .filter_map(|fragment| match fragment {
                 Fragment::Use { content } => Some(content.to_string()),
                 _ => None,
             })
-            .join("\n");
+            .collect_vec();
 
-        let lib_use_text = format!("{}\nuse crate::*;", use_text);
+        let use_text = use_fragments.join("\n");
+
+        let mut defs = HashMap::<String, String>::new();
+        for fragment in &fragments {
+            match fragment {
+                Fragment::Function { name, .. } => {
+                    defs.insert(name.to_string(), "functions".to_string());
+                }
+                Fragment::ApiResource { name, .. } => {
+                    defs.insert(name.to_string(), "resources".to_string());
+                }
+                Fragment::Model { content, .. } => {
+                    for ident in defined_idents(content) {
+                        defs.insert(ident, "models".to_string());
+                    }
+                }
+                Fragment::Const { content } => {
+                    for ident in defined_idents(content) {
+                        defs.insert(ident, "consts".to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !legacy_glob {
+            let mut unresolved = std::collections::BTreeSet::new();
+            for fragment in &fragments {
+                let content = match fragment {
+                    Fragment::Function { content, .. }
+                    | Fragment::ApiResource { content, .. }
+                    | Fragment::Model { content, .. }
+                    | Fragment::Const { content }
+                    | Fragment::Common { content } => content.as_str(),
+                    _ => continue,
+                };
+                unresolved.extend(unresolved_symbols(content, &defs, &use_fragments));
+            }
+            for name in &unresolved {
+                eprintln!("warning: reference to undefined symbol `{}`", name);
+            }
+        }
+
+        let imports =
+            |content: &str| module_imports(content, &defs, &use_fragments, legacy_glob);
 
         let modules = fragments
             .iter()
             .filter_map(|fragment| match fragment {
                 Fragment::Function { name, content } => Some(Module::Lib {
                     name: name.to_string(),
-                    content: lib_use_text.to_owned() + content,
+                    content: imports(content) + content,
                     modules: vec![],
                 }),
                 _ => None,
@@ -402,18 +813,49 @@ impl Modules for Vec<Module> {
         let modules = fragments
             .iter()
             .filter_map(|fragment| match fragment {
-                Fragment::ApiResource { name, content } => Some(Module::Lib {
+                Fragment::ApiResource { name, content, .. } => Some(Module::Lib {
                     name: name.to_string(),
-                    content: lib_use_text.to_owned() + content,
+                    content: imports(content) + content,
                     modules: vec![],
                 }),
                 _ => None,
             })
             .collect_vec();
 
+        let routes = fragments
+            .iter()
+            .filter_map(|fragment| match fragment {
+                Fragment::ApiResource {
+                    name, method, path, ..
+                } => Some(format!("    (\"{}\", \"{}\", \"{}\"),", method, path, name)),
+                _ => None,
+            })
+            .collect_vec();
+
+        let services = fragments
+            .iter()
+            .filter_map(|fragment| match fragment {
+                Fragment::ApiResource { name, .. } => Some(format!("    cfg.service({});", name)),
+                _ => None,
+            })
+            .collect_vec();
+
+        // Only emit the route-registration table when the project actually has routes, so
+        // route-free projects don't gain an empty table or a hard-wired actix_web dependency.
+        let route_table = if routes.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\npub const ROUTES: &[(&str, &str, &str)] = &[\n{}\n];\n\
+                 pub fn register(cfg: &mut actix_web::web::ServiceConfig) {{\n{}\n}}\n",
+                routes.join("\n"),
+                services.join("\n")
+            )
+        };
+
         let api_resources = Module::Lib {
             name: "resources".to_string(),
-            content: modules.mod_text(),
+            content: modules.mod_text() + route_table.as_str(),
             modules,
         };
 
@@ -422,7 +864,7 @@ impl Modules for Vec<Module> {
             .filter_map(|fragment| match fragment {
                 Fragment::Model { name, content } => Some(Module::Lib {
                     name: name.to_string(),
-                    content: lib_use_text.to_owned() + content,
+                    content: imports(content) + content,
                     modules: vec![],
                 }),
                 _ => None,
@@ -435,17 +877,17 @@ impl Modules for Vec<Module> {
             modules,
         };
 
+        let consts_content = fragments
+            .iter()
+            .filter_map(|fragment| match fragment {
+                Fragment::Const { content } => Some(content.to_string()),
+                _ => None,
+            })
+            .join("\n");
+
         let consts = Module::Lib {
             name: "consts".to_string(),
-            content: lib_use_text.to_owned()
-                + fragments
-                    .iter()
-                    .filter_map(|fragment| match fragment {
-                        Fragment::Const { content } => Some(content.to_string()),
-                        _ => None,
-                    })
-                    .join("\n")
-                    .as_str(),
+            content: imports(&consts_content) + consts_content.as_str(),
             modules: vec![],
         };
 
@@ -457,7 +899,7 @@ impl Modules for Vec<Module> {
                         name,
                         fragments: Some(fragments),
                     } => {
-                        let modules = Vec::<Module>::parse(fragments.to_owned())?;
+                        let modules = Vec::<Module>::parse(fragments.to_owned(), legacy_glob)?;
                         Some(Module::Lib {
                             name: name.to_string(),
                             content: modules.mod_text(),
@@ -485,7 +927,7 @@ impl Modules for Vec<Module> {
         if !content.is_empty() {
             let common = Module::Lib {
                 name: "common".to_string(),
-                content: lib_use_text.to_owned() + content.as_str(),
+                content: imports(&content) + content.as_str(),
                 modules: vec![],
             };
             res.push(common);
@@ -540,13 +982,21 @@ struct Code {
 }
 
 impl Code {
-    pub fn parse(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
-        let path = path.into().to_string_lossy().to_string();
-
-        let content = fs::read_to_string(path)?;
+    pub fn parse(path: impl Into<PathBuf>, legacy_glob: bool) -> anyhow::Result<Self> {
+        let path = path.into();
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+
+        // Re-running on this tool's own output would otherwise re-ingest the bucket modules as
+        // user modules and nest them (`functions/functions/…`). Merge an already-split tree back
+        // into a single source first so the reorganizer is idempotent.
+        let content = if is_split_output(&base_dir) {
+            merge(&base_dir)?
+        } else {
+            fs::read_to_string(&path)?
+        };
 
-        let fragments = Vec::<Fragment>::parse(&content)?;
-        let modules = Vec::<Module>::parse(fragments)?;
+        let fragments = Vec::<Fragment>::parse_dir(&content, &base_dir)?;
+        let modules = Vec::<Module>::parse(fragments, legacy_glob)?;
 
         Ok(Self { modules })
     }
@@ -558,11 +1008,275 @@ impl Code {
     }
 }
 
+fn strip_pub(item: &mut Item) {
+    match item {
+        Item::Fn(i) => i.vis = Visibility::Inherited,
+        Item::Struct(i) => {
+            i.vis = Visibility::Inherited;
+            i.fields
+                .iter_mut()
+                .for_each(|field| field.vis = Visibility::Inherited);
+        }
+        Item::Enum(i) => i.vis = Visibility::Inherited,
+        Item::Const(i) => i.vis = Visibility::Inherited,
+        Item::Static(i) => i.vis = Visibility::Inherited,
+        Item::Trait(i) => i.vis = Visibility::Inherited,
+        Item::TraitAlias(i) => i.vis = Visibility::Inherited,
+        Item::Type(i) => i.vis = Visibility::Inherited,
+        Item::Union(i) => {
+            i.vis = Visibility::Inherited;
+            i.fields
+                .named
+                .iter_mut()
+                .for_each(|field| field.vis = Visibility::Inherited);
+        }
+        _ => {}
+    }
+}
+
+fn use_root(tree: &syn::UseTree) -> Option<String> {
+    match tree {
+        syn::UseTree::Path(p) => Some(p.ident.to_string()),
+        syn::UseTree::Name(n) => Some(n.ident.to_string()),
+        syn::UseTree::Rename(r) => Some(r.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn is_bucket(name: &str) -> bool {
+    matches!(
+        name,
+        "functions" | "resources" | "models" | "consts" | "common"
+    )
+}
+
+fn is_generated_route_item(item: &Item) -> bool {
+    match item {
+        Item::Const(item) => item.ident == "ROUTES",
+        Item::Fn(item) => item.sig.ident == "register",
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct Merge {
+    uses: std::collections::BTreeSet<String>,
+    models: Vec<String>,
+    functions: Vec<String>,
+    resources: Vec<String>,
+    consts: Vec<String>,
+    common: Vec<String>,
+    entry: Vec<String>,
+}
+
+impl Merge {
+    fn push(&mut self, bucket: Option<&str>, is_main: bool, text: String) {
+        if is_main {
+            self.entry.push(text);
+            return;
+        }
+        match bucket {
+            Some("functions") => self.functions.push(text),
+            Some("resources") => self.resources.push(text),
+            Some("models") => self.models.push(text),
+            Some("consts") => self.consts.push(text),
+            _ => self.common.push(text),
+        }
+    }
+
+    fn read_file(&mut self, file_path: &Path, bucket: Option<&str>) -> anyhow::Result<()> {
+        let content = fs::read_to_string(file_path)?;
+        let ast = syn::parse_file(&content)?;
+
+        let stem = file_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let parent = file_path.parent().unwrap_or_else(|| Path::new("."));
+        let children_dir = if stem == "main" {
+            parent.to_path_buf()
+        } else {
+            parent.join(&stem)
+        };
+
+        for item in ast.items {
+            match item {
+                Item::Use(item) => {
+                    // Drop the `use crate::*;` / `pub use self::X::*;` glue the splitter injects.
+                    match use_root(&item.tree).as_deref() {
+                        Some("crate") | Some("self") => continue,
+                        _ => {}
+                    }
+                    self.uses.insert(item.to_token_stream().to_string());
+                }
+                Item::Mod(item) if item.content.is_none() => {
+                    let name = item.ident.to_string();
+                    let child_path = children_dir.join(format!("{}.rs", name));
+                    match bucket {
+                        // Already inside a splitter bucket: a per-item submodule that keeps
+                        // flattening into the same bucket.
+                        Some(_) => self.read_file(&child_path, bucket)?,
+                        // Top-level splitter aggregation module.
+                        None if is_bucket(&name) => self.read_file(&child_path, Some(&name))?,
+                        // User-authored module: reconstruct it as a real `mod name { … }` block.
+                        None => {
+                            let mut inner = Merge::default();
+                            inner.read_file(&child_path, None)?;
+                            self.common
+                                .push(format!("mod {} {{\n{}\n}}", name, inner.render()));
+                        }
+                    }
+                }
+                mut item => {
+                    if bucket == Some("resources") && is_generated_route_item(&item) {
+                        continue;
+                    }
+                    let is_main = matches!(&item, Item::Fn(f) if f.sig.ident == "main");
+                    strip_pub(&mut item);
+                    self.push(bucket, is_main, item.to_token_stream().to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut sections = vec![self.uses.iter().join("\n")];
+        for section in [
+            &self.models,
+            &self.functions,
+            &self.resources,
+            &self.consts,
+            &self.common,
+            &self.entry,
+        ] {
+            if !section.is_empty() {
+                sections.push(section.join("\n"));
+            }
+        }
+        sections.join("\n")
+    }
+}
+
+fn merge(src_path: &Path) -> anyhow::Result<String> {
+    let mut merge = Merge::default();
+    merge.read_file(&src_path.join("main.rs"), None)?;
+    Ok(merge.render())
+}
+
+/// Whether `src_path` already holds this tool's own output, i.e. at least one top-level bucket
+/// aggregation file sits next to `main.rs`.
+fn is_split_output(src_path: &Path) -> bool {
+    ["resources", "functions", "models", "consts"]
+        .iter()
+        .any(|name| src_path.join(format!("{}.rs", name)).exists())
+}
+
+fn span_range(input: &str, item: &Item) -> std::ops::Range<usize> {
+    byte_offset(input, item.span().start())..byte_offset(input, item.span().end())
+}
+
+fn parse_error_diagnostic(input: &str, err: &syn::Error) -> Diagnostic<()> {
+    let span = err.span();
+    let range = byte_offset(input, span.start())..byte_offset(input, span.end());
+    Diagnostic::error()
+        .with_message(err.to_string())
+        .with_labels(vec![Label::primary((), range)])
+}
+
+fn base_type_ident(ty: &syn::Type) -> Option<String> {
+    match ty {
+        syn::Type::Path(ty) => ty.path.segments.last().map(|seg| seg.ident.to_string()),
+        syn::Type::Reference(ty) => base_type_ident(&ty.elem),
+        syn::Type::Group(ty) => base_type_ident(&ty.elem),
+        syn::Type::Paren(ty) => base_type_ident(&ty.elem),
+        _ => None,
+    }
+}
+
+fn build_diagnostics(input: &str, items: &[Item]) -> Vec<Diagnostic<()>> {
+    let mut diagnostics = vec![];
+
+    let mut type_defs = HashMap::<String, Vec<std::ops::Range<usize>>>::new();
+    for item in items {
+        let name = match item {
+            Item::Struct(i) => Some(i.ident.to_string().to_snake_case()),
+            Item::Enum(i) => Some(i.ident.to_string().to_snake_case()),
+            Item::Trait(i) => Some(i.ident.to_string().to_snake_case()),
+            Item::TraitAlias(i) => Some(i.ident.to_string().to_snake_case()),
+            Item::Type(i) => Some(i.ident.to_string().to_snake_case()),
+            Item::Union(i) => Some(i.ident.to_string().to_snake_case()),
+            _ => None,
+        };
+        if let Some(name) = name {
+            type_defs.entry(name).or_default().push(span_range(input, item));
+        }
+    }
+
+    for (name, ranges) in &type_defs {
+        if ranges.len() > 1 {
+            let labels = ranges
+                .iter()
+                .map(|range| Label::secondary((), range.clone()))
+                .collect_vec();
+            diagnostics.push(
+                Diagnostic::warning()
+                    .with_message(format!("duplicate definition `{}` collapsed into one model", name))
+                    .with_labels(labels),
+            );
+        }
+    }
+
+    for item in items {
+        if let Item::Impl(imp) = item {
+            // Compare against the normalized base type (`impl<T> Foo<T>`, `impl Trait for &Foo`
+            // both resolve to `foo`) so ordinary generic/reference impls don't warn spuriously.
+            if let Some(name) = base_type_ident(&imp.self_ty).map(|ident| ident.to_snake_case()) {
+                if !type_defs.contains_key(&name) {
+                    diagnostics.push(
+                        Diagnostic::warning()
+                            .with_message(format!(
+                                "`impl {}` has no matching model in this file",
+                                name
+                            ))
+                            .with_labels(vec![Label::primary((), span_range(input, item))]),
+                    );
+                }
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn emit_diagnostics(file_name: &str, input: &str, diagnostics: &[Diagnostic<()>]) -> anyhow::Result<()> {
+    let file = SimpleFile::new(file_name, input);
+    let writer = StandardStream::stderr(ColorChoice::Auto);
+    let config = term::Config::default();
+    for diagnostic in diagnostics {
+        term::emit(&mut writer.lock(), &config, &file, diagnostic)?;
+    }
+    Ok(())
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "isucon-restruct")]
 struct Opt {
     #[structopt(short, long)]
     path: PathBuf,
+
+    #[structopt(long)]
+    legacy_glob_imports: bool,
+
+    #[structopt(long)]
+    merge: bool,
+
+    #[structopt(long)]
+    dry_run: bool,
+
+    #[structopt(long, default_value = "rust")]
+    format: String,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -578,7 +1292,38 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    let code = Code::parse(entry_point_path)?;
+    if opt.merge {
+        print!("{}", merge(&src_path)?);
+        return Ok(());
+    }
+
+    if opt.dry_run {
+        let input = fs::read_to_string(&entry_point_path)?;
+        let manifest = manifest(&input)?;
+        match opt.format.as_str() {
+            "json" => println!("{}", serde_json::to_string_pretty(&manifest)?),
+            _ => println!("{:#?}", Vec::<Fragment>::parse(&input)?),
+        }
+        return Ok(());
+    }
+
+    let input = fs::read_to_string(&entry_point_path)?;
+    let file_name = entry_point_path.to_string_lossy().to_string();
+
+    let ast = match syn::parse_file(&input) {
+        Ok(ast) => ast,
+        Err(err) => {
+            emit_diagnostics(&file_name, &input, &[parse_error_diagnostic(&input, &err)])?;
+            return Ok(());
+        }
+    };
+
+    let diagnostics = build_diagnostics(&input, &ast.items);
+    if !diagnostics.is_empty() {
+        emit_diagnostics(&file_name, &input, &diagnostics)?;
+    }
+
+    let code = Code::parse(entry_point_path, opt.legacy_glob_imports)?;
 
     code.write(&src_path)?;
 